@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Builds the cloud-init seed ("cidata") block device, in either the
+//! default NoCloud layout or an OpenStack Config Drive v2 layout.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use propolis::block;
+
+use crate::config::{CloudInit, CloudInitFormat, Config};
+
+fn load_content(
+    inline: &Option<String>,
+    path: &Option<String>,
+    what: &str,
+) -> anyhow::Result<Option<String>> {
+    match (inline, path) {
+        (Some(data), None) => Ok(Some(data.clone())),
+        (None, Some(path)) => {
+            let data = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {what} from {path}"))?;
+            Ok(Some(data))
+        }
+        (None, None) => Ok(None),
+        (Some(_), Some(_)) => {
+            anyhow::bail!("only one of {what}/{what}_path may be set")
+        }
+    }
+}
+
+/// Convert a YAML blob (as accepted by the NoCloud datasource for
+/// meta-data/network-config/vendor-data) into the JSON shape the
+/// OpenStack config-drive datasource expects for the equivalent file.
+///
+/// OpenStack's `network_data.json`/`vendor_data.json` schemas don't match
+/// NoCloud's `network-config`/`vendor-data` YAML schemas; this only
+/// guarantees the output is syntactically valid JSON carrying the same
+/// data, not that it conforms to the OpenStack schema for that file. An
+/// operator targeting config-drive should supply `network_config`/
+/// `vendor_data` already shaped per the OpenStack schema (just encoded as
+/// YAML, since that's what this config format accepts).
+fn yaml_to_json(yaml: &str) -> anyhow::Result<String> {
+    let value: serde_yaml::Value =
+        serde_yaml::from_str(yaml).context("parsing input as YAML")?;
+    serde_json::to_string_pretty(&value).context("re-encoding input as JSON")
+}
+
+fn build_nocloud_iso(ci: &CloudInit) -> anyhow::Result<Vec<u8>> {
+    let user_data =
+        load_content(&ci.user_data, &ci.user_data_path, "user_data")?
+            .unwrap_or_default();
+    let meta_data =
+        load_content(&ci.meta_data, &ci.meta_data_path, "meta_data")?
+            .unwrap_or_default();
+    let network_config = load_content(
+        &ci.network_config,
+        &ci.network_config_path,
+        "network_config",
+    )?;
+    let vendor_data =
+        load_content(&ci.vendor_data, &ci.vendor_data_path, "vendor_data")?;
+
+    let mut files = vec![
+        ("user-data", user_data.into_bytes()),
+        ("meta-data", meta_data.into_bytes()),
+    ];
+    if let Some(network_config) = network_config {
+        files.push(("network-config", network_config.into_bytes()));
+    }
+    if let Some(vendor_data) = vendor_data {
+        files.push(("vendor-data", vendor_data.into_bytes()));
+    }
+
+    cidata_fs::build_iso9660("cidata", &files)
+}
+
+fn build_config_drive_iso(ci: &CloudInit) -> anyhow::Result<Vec<u8>> {
+    let user_data =
+        load_content(&ci.user_data, &ci.user_data_path, "user_data")?
+            .unwrap_or_default();
+    let meta_data_yaml =
+        load_content(&ci.meta_data, &ci.meta_data_path, "meta_data")?
+            .unwrap_or_else(|| "{}".to_string());
+    let network_data_yaml = load_content(
+        &ci.network_config,
+        &ci.network_config_path,
+        "network_config",
+    )?
+    .unwrap_or_else(|| "{}".to_string());
+    let vendor_data_yaml =
+        load_content(&ci.vendor_data, &ci.vendor_data_path, "vendor_data")?
+            .unwrap_or_else(|| "{}".to_string());
+
+    let meta_data_json = yaml_to_json(&meta_data_yaml)
+        .context("converting meta_data to meta_data.json")?;
+    let network_data_json = yaml_to_json(&network_data_yaml)
+        .context("converting network_config to network_data.json")?;
+    let vendor_data_json = yaml_to_json(&vendor_data_yaml)
+        .context("converting vendor_data to vendor_data.json")?;
+
+    let files = vec![
+        ("openstack/latest/meta_data.json", meta_data_json.into_bytes()),
+        ("openstack/latest/user_data", user_data.into_bytes()),
+        (
+            "openstack/latest/network_data.json",
+            network_data_json.into_bytes(),
+        ),
+        (
+            "openstack/latest/vendor_data.json",
+            vendor_data_json.into_bytes(),
+        ),
+    ];
+
+    cidata_fs::build_iso9660("config-2", &files)
+}
+
+pub fn build_cidata_be(
+    config: &Config,
+) -> anyhow::Result<Arc<dyn block::Backend>> {
+    let ci = config
+        .cloudinit
+        .as_ref()
+        .context("no [cloudinit] section present in config")?;
+
+    let image = match ci.format {
+        CloudInitFormat::NoCloud => build_nocloud_iso(ci)?,
+        CloudInitFormat::ConfigDrive => build_config_drive_iso(ci)?,
+    };
+
+    Ok(block::InMemoryBackend::create(
+        image,
+        block::BackendOpts {
+            block_size: None,
+            read_only: Some(true),
+            skip_flush: None,
+        },
+    )?)
+}
+
+/// Assembles a small ISO9660 volume (as cloud-init datasources expect) by
+/// shelling out to whichever of the common mkisofs-compatible tools is
+/// available on the host.
+///
+/// This is a new runtime dependency introduced by config-drive support: the
+/// `cidata` block device (NoCloud *and* config-drive alike) now requires one
+/// of `genisoimage`/`mkisofs`/`xorriso` to be installed wherever
+/// `propolis-standalone` runs with a `[cloudinit]` section configured.
+mod cidata_fs {
+    use std::io::Write;
+
+    use anyhow::Context;
+
+    const ISO_TOOLS: &[&str] = &["genisoimage", "mkisofs", "xorriso"];
+
+    pub fn build_iso9660(
+        volume_label: &str,
+        files: &[(&str, Vec<u8>)],
+    ) -> anyhow::Result<Vec<u8>> {
+        let staging = tempfile::tempdir()
+            .context("creating staging dir for cidata image")?;
+        for (name, contents) in files {
+            let dest = staging.path().join(name);
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent).with_context(|| {
+                    format!("creating {} in staging dir", parent.display())
+                })?;
+            }
+            std::fs::File::create(&dest)
+                .and_then(|mut f| f.write_all(contents))
+                .with_context(|| {
+                    format!("writing {name} to staging dir")
+                })?;
+        }
+
+        let out_path = staging.path().join("cidata.iso");
+        let tool = ISO_TOOLS
+            .iter()
+            .find(|tool| which(tool))
+            .with_context(|| {
+                format!(
+                    "no ISO9660 authoring tool found (tried: {})",
+                    ISO_TOOLS.join(", "),
+                )
+            })?;
+
+        let mut cmd = std::process::Command::new(tool);
+        if *tool == "xorriso" {
+            // Native xorriso doesn't speak the mkisofs CLI dialect used
+            // below; `-as mkisofs` switches it into a compatible frontend.
+            cmd.arg("-as").arg("mkisofs");
+        }
+        let status = cmd
+            .args(["-output"])
+            .arg(&out_path)
+            .args(["-volid", volume_label, "-joliet", "-rock"])
+            .arg(staging.path())
+            .status()
+            .with_context(|| format!("running {tool}"))?;
+        anyhow::ensure!(status.success(), "{tool} exited with {status}");
+
+        std::fs::read(&out_path).context("reading generated cidata image")
+    }
+
+    fn which(name: &str) -> bool {
+        std::env::var_os("PATH")
+            .map(|paths| {
+                std::env::split_paths(&paths)
+                    .any(|dir| dir.join(name).is_file())
+            })
+            .unwrap_or(false)
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn build_iso9660_round_trips_file_contents() {
+            if !ISO_TOOLS.iter().any(|t| which(t)) {
+                eprintln!(
+                    "skipping: none of {ISO_TOOLS:?} found on PATH"
+                );
+                return;
+            }
+
+            let files = [("meta-data", b"instance-id: test\n".to_vec())];
+            let image = build_iso9660("cidata", &files).unwrap();
+
+            // A full ISO9660 parse is overkill here; just confirm the
+            // authoring tool ran and produced a non-trivial image
+            // containing our content. The volume label is not checked.
+            assert!(image.len() > 2048);
+            let haystack = String::from_utf8_lossy(&image);
+            assert!(haystack.contains("instance-id: test"));
+        }
+    }
+}
@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::collections::BTreeMap;
-use std::num::NonZeroUsize;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::os::unix::fs::FileTypeExt;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -67,6 +67,15 @@ pub struct Main {
 
     /// Request bootrom override boot order using the devices specified
     pub boot_order: Option<Vec<String>>,
+
+    /// Path to a file backing the bootrom's persistent NVRAM variable
+    /// store (boot entries, `BootOrder`, enrolled keys, etc).
+    ///
+    /// Only [`NvramStore::load`] reads this field; within this crate no
+    /// bootrom setup or shutdown path constructs an [`NvramStore`].
+    ///
+    /// Default: None, firmware NVRAM state does not persist across runs
+    pub nvram_path: Option<String>,
 }
 
 /// A hard-coded device, either enabled by default or accessible locally
@@ -98,23 +107,53 @@ pub struct BlockDevice {
     pub options: BTreeMap<String, toml::Value>,
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CloudInitFormat {
+    #[default]
+    #[serde(rename = "nocloud")]
+    NoCloud,
+    ConfigDrive,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct CloudInit {
+    #[serde(default)]
+    pub format: CloudInitFormat,
+
     pub user_data: Option<String>,
     pub meta_data: Option<String>,
     pub network_config: Option<String>,
+    pub vendor_data: Option<String>,
 
     // allow path-style contents as well
     pub user_data_path: Option<String>,
     pub meta_data_path: Option<String>,
     pub network_config_path: Option<String>,
+    pub vendor_data_path: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct FileConfig {
     path: String,
     workers: Option<NonZeroUsize>,
+
+    /// Read-only source image to clone `path` from at create time, rather
+    /// than opening `path` directly.
+    base: Option<String>,
+    /// When set alongside `base`, materialize `path` as a clone of `base`:
+    /// a copy-on-write reflink where the filesystem supports it, otherwise
+    /// a full copy (see [`clone_base_image`]).
+    #[serde(default)]
+    clone: bool,
+}
+#[derive(Deserialize)]
+struct UringFileConfig {
+    path: String,
+    queue_depth: Option<NonZeroU32>,
+    #[serde(default)]
+    sqpoll: bool,
 }
 #[derive(Deserialize)]
 struct MemAsyncConfig {
@@ -161,6 +200,100 @@ fn opt_deser<'de, T: Deserialize<'de>>(
 
 const DEFAULT_WORKER_COUNT: usize = 8;
 const MAX_FILE_WORKERS: usize = 32;
+const DEFAULT_URING_QUEUE_DEPTH: u32 = 128;
+
+/// Which mechanism [`clone_base_image`] actually used to materialize the
+/// clone. Exposed (rather than collapsed to `()`) so callers/tests can
+/// confirm the fast path is really being taken, instead of silently
+/// degrading to a full copy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CloneMethod {
+    Reflink,
+    CopyFileRange,
+    Buffered,
+}
+
+/// Materialize `dest` as a clone of the read-only `base` image, preferring
+/// the cheapest mechanism the host/filesystem will support:
+///
+/// 1. a `FICLONE` reflink, for instant copy-on-write on btrfs/XFS
+/// 2. a `copy_file_range(2)` loop, letting the kernel perform the copy
+/// 3. a plain buffered read/write copy, as a last resort
+fn clone_base_image(
+    base: &str,
+    dest: &str,
+    log: &slog::Logger,
+) -> anyhow::Result<CloneMethod> {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    let src = File::open(base)
+        .with_context(|| format!("opening base image {base}"))?;
+    let dst = File::create(dest)
+        .with_context(|| format!("creating clone destination {dest}"))?;
+
+    // Try FICLONE first: an instant, space-sharing reflink.
+    let ficlone_rc =
+        unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE, src.as_raw_fd()) };
+    if ficlone_rc == 0 {
+        return Ok(CloneMethod::Reflink);
+    }
+    let ficlone_err = std::io::Error::last_os_error();
+    slog::debug!(
+        log,
+        "FICLONE unavailable for {base} -> {dest}, trying copy_file_range: {ficlone_err}"
+    );
+
+    let len = src.metadata()?.len();
+    dst.set_len(len)?;
+    let copy_file_range_result = (|| -> std::io::Result<()> {
+        let mut off = 0u64;
+        while off < len {
+            let rc = unsafe {
+                libc::copy_file_range(
+                    src.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    dst.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    (len - off) as usize,
+                    0,
+                )
+            };
+            if rc < 0 {
+                return Err(std::io::Error::last_os_error());
+            } else if rc == 0 {
+                break;
+            }
+            off += rc as u64;
+        }
+        Ok(())
+    })();
+    match copy_file_range_result {
+        Ok(()) => return Ok(CloneMethod::CopyFileRange),
+        Err(e)
+            if e.raw_os_error() == Some(libc::ENOSYS)
+                || e.raw_os_error() == Some(libc::EXDEV) =>
+        {
+            slog::debug!(
+                log,
+                "copy_file_range unavailable for {base} -> {dest}, \
+                falling back to buffered copy: {e}"
+            );
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    std::io::copy(&mut File::open(base)?, &mut File::create(dest)?)
+        .with_context(|| format!("copying {base} -> {dest}"))?;
+    Ok(CloneMethod::Buffered)
+}
+
+// `FICLONE` from linux/fs.h: `_IOW(0x94, 9, int)`.
+//   (_IOC_WRITE << 30) | (size_of::<c_int>() << 16) | (0x94 << 8) | 9
+//     = 0x40000000 | 0x00040000 | 0x00009400 | 0x00000009
+//     = 0x40049409
+#[cfg(target_os = "linux")]
+const FICLONE: libc::c_ulong = 0x4004_9409;
 
 pub fn block_backend(
     config: &Config,
@@ -181,6 +314,22 @@ pub fn block_backend(
         "file" => {
             let parsed: FileConfig = opt_deser(&be.options).unwrap();
 
+            if let Some(base) = &parsed.base {
+                assert!(
+                    parsed.clone,
+                    "\"base\" requires \"clone = true\" for block device \"{backend_name}\""
+                );
+                let method = clone_base_image(base, &parsed.path, log)
+                    .unwrap_or_else(|e| {
+                        panic!(
+                            "failed to clone base image {base} -> {} for \"{backend_name}\": {e}",
+                            parsed.path,
+                        )
+                    });
+                slog::info!(log, "cloned base image for \"{backend_name}\"";
+                    "base" => base, "method" => format!("{method:?}"));
+            }
+
             // Check if raw device is being used and gripe if it isn't
             let meta = std::fs::metadata(&parsed.path)
                 .with_context(|| {
@@ -215,6 +364,50 @@ pub fn block_backend(
             };
             block::FileBackend::create(&parsed.path, opts, workers).unwrap()
         }
+        "file-uring" => {
+            let parsed: UringFileConfig = opt_deser(&be.options).unwrap();
+
+            let meta = std::fs::metadata(&parsed.path)
+                .with_context(|| {
+                    format!(
+                        "opening {} for block device \"{backend_name}\"",
+                        parsed.path,
+                    )
+                })
+                .expect("file device path is valid");
+
+            if meta.file_type().is_block_device() {
+                slog::warn!(log, "Block backend using standard device rather than raw";
+                    "path" => &parsed.path);
+            }
+
+            let queue_depth = parsed
+                .queue_depth
+                .map(NonZeroU32::get)
+                .unwrap_or(DEFAULT_URING_QUEUE_DEPTH);
+
+            match block::UringFileBackend::create(
+                &parsed.path,
+                opts,
+                queue_depth,
+                parsed.sqpoll,
+                log.clone(),
+            ) {
+                Ok(be) => be,
+                Err(e) => {
+                    slog::warn!(
+                        log,
+                        "io_uring unavailable for \"{backend_name}\", \
+                        falling back to threaded file backend";
+                        "error" => %e,
+                    );
+                    let workers =
+                        NonZeroUsize::new(DEFAULT_WORKER_COUNT).unwrap();
+                    block::FileBackend::create(&parsed.path, opts, workers)
+                        .unwrap()
+                }
+            }
+        }
         "mem-async" => {
             let parsed: MemAsyncConfig = opt_deser(&be.options).unwrap();
 
@@ -236,6 +429,74 @@ pub fn block_backend(
     (be, backend_name.into())
 }
 
+fn load_nvram(path: &str) -> anyhow::Result<Vec<u8>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => {
+            Err(e).with_context(|| format!("reading nvram store {path}"))
+        }
+    }
+}
+
+fn flush_nvram(path: &str, data: &[u8]) -> anyhow::Result<()> {
+    std::fs::write(path, data)
+        .with_context(|| format!("writing nvram store {path}"))
+}
+
+/// A handle on the bootrom's persistent NVRAM variable store (boot entries,
+/// `BootOrder`, enrolled keys, etc), backed by `main.nvram_path`.
+///
+/// Construct one with [`NvramStore::load`] when wiring up the bootrom so its
+/// writable NVRAM region can be pointed at [`NvramStore::data_mut`]; the
+/// store is flushed back to disk both explicitly, via [`NvramStore::flush`]
+/// (e.g. from an instance's shutdown handling), and automatically when
+/// dropped, so firmware state still persists even if that hook is missed.
+///
+/// NOTE: within this crate, only the unit tests below construct an
+/// `NvramStore`. No bootrom construction or instance-shutdown code here
+/// calls `NvramStore::load` or `NvramStore::flush`, so setting
+/// `main.nvram_path` alone does not persist any firmware state.
+pub struct NvramStore {
+    path: String,
+    data: Vec<u8>,
+}
+
+impl NvramStore {
+    /// Load the persistent NVRAM image for `main`, if `nvram_path` is
+    /// configured. Returns `None` when it isn't, in which case the bootrom
+    /// should fall back to its normal ephemeral, in-memory store.
+    pub fn load(main: &Main) -> anyhow::Result<Option<Self>> {
+        let Some(path) = &main.nvram_path else {
+            return Ok(None);
+        };
+        let data = load_nvram(path)?;
+        Ok(Some(Self { path: path.clone(), data }))
+    }
+
+    /// The bootrom's writable NVRAM region, to be handed to the firmware.
+    pub fn data_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    /// Flush the current contents back to `nvram_path` immediately, rather
+    /// than waiting for this store to be dropped.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        flush_nvram(&self.path, &self.data)
+    }
+}
+
+impl Drop for NvramStore {
+    fn drop(&mut self) {
+        // Best-effort: if an explicit `flush()` already ran (e.g. as part
+        // of orderly instance shutdown) this just re-writes the same
+        // contents. Errors here can't be meaningfully propagated from a
+        // `Drop` impl, so they're swallowed rather than panicking during
+        // unwind/teardown.
+        let _ = self.flush();
+    }
+}
+
 pub fn parse(path: &str) -> anyhow::Result<Config> {
     let file_data =
         std::fs::read(path).context("Failed to read given config.toml")?;
@@ -289,3 +550,149 @@ pub fn parse_cpuid(config: &Config) -> anyhow::Result<Option<CpuidSet>> {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn test_log() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    #[test]
+    fn cloudinit_format_parses_config_strings() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            format: CloudInitFormat,
+        }
+
+        let parsed: Wrapper =
+            toml::from_str(r#"format = "nocloud""#).unwrap();
+        assert_eq!(parsed.format, CloudInitFormat::NoCloud);
+
+        let parsed: Wrapper =
+            toml::from_str(r#"format = "config-drive""#).unwrap();
+        assert_eq!(parsed.format, CloudInitFormat::ConfigDrive);
+    }
+
+    #[test]
+    fn ficlone_matches_kernel_definition() {
+        // FICLONE is `_IOW(0x94, 9, int)` per linux/fs.h. Compute it
+        // independently from the ioctl encoding rules so a transposed
+        // nibble (or similar typo) in the constant above is caught here,
+        // rather than only manifesting as a mysteriously-never-taken
+        // reflink fast path.
+        const IOC_WRITE: libc::c_ulong = 1;
+        let computed = (IOC_WRITE << 30)
+            | ((std::mem::size_of::<libc::c_int>() as libc::c_ulong) << 16)
+            | (0x94 << 8)
+            | 9;
+        assert_eq!(FICLONE, computed);
+    }
+
+    #[test]
+    fn clone_base_image_copies_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.img");
+        let dest = dir.path().join("clone.img");
+
+        std::fs::File::create(&base)
+            .unwrap()
+            .write_all(b"golden image contents")
+            .unwrap();
+
+        let method = clone_base_image(
+            base.to_str().unwrap(),
+            dest.to_str().unwrap(),
+            &test_log(),
+        )
+        .unwrap();
+
+        // Whichever mechanism actually ran, the contents must match; but we
+        // also pin down *which* one so a regression that silently demotes
+        // every clone to the buffered fallback (like the bad FICLONE value
+        // previously did) shows up as a test failure rather than going
+        // unnoticed.
+        assert_ne!(method, CloneMethod::Buffered);
+        assert_eq!(
+            std::fs::read(&dest).unwrap(),
+            b"golden image contents".to_vec(),
+        );
+    }
+
+    #[test]
+    fn clone_base_image_falls_back_on_exdev() {
+        // copy_file_range(2) cannot cross filesystem boundaries, returning
+        // EXDEV; /tmp and /dev/shm are commonly on different filesystems,
+        // giving coverage of the buffered-copy fallback path without
+        // needing to fake the syscall. A reflink across filesystems is
+        // similarly impossible, so this path must bottom out at
+        // `CloneMethod::Buffered`.
+        let base_dir = tempfile::Builder::new()
+            .prefix("clone-base-")
+            .tempdir_in("/dev/shm")
+            .unwrap_or_else(|_| tempfile::tempdir().unwrap());
+        let dest_dir = tempfile::tempdir().unwrap();
+
+        let base = base_dir.path().join("base.img");
+        let dest = dest_dir.path().join("clone.img");
+        std::fs::File::create(&base)
+            .unwrap()
+            .write_all(b"cross-filesystem clone")
+            .unwrap();
+
+        let method = clone_base_image(
+            base.to_str().unwrap(),
+            dest.to_str().unwrap(),
+            &test_log(),
+        )
+        .unwrap();
+
+        assert_eq!(method, CloneMethod::Buffered);
+        assert_eq!(
+            std::fs::read(&dest).unwrap(),
+            b"cross-filesystem clone".to_vec(),
+        );
+    }
+
+    fn test_main(nvram_path: Option<String>) -> Main {
+        Main {
+            name: "test".to_string(),
+            cpus: 1,
+            bootrom: "bootrom.fd".to_string(),
+            bootrom_version: None,
+            memory: 512,
+            use_reservoir: None,
+            cpuid_profile: None,
+            exit_on_halt: 0,
+            exit_on_reboot: None,
+            boot_order: None,
+            nvram_path,
+        }
+    }
+
+    #[test]
+    fn nvram_store_none_without_path() {
+        assert!(NvramStore::load(&test_main(None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn nvram_store_round_trips_and_flushes_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nvram.bin");
+        let main = test_main(Some(path.to_str().unwrap().to_string()));
+
+        {
+            let mut store = NvramStore::load(&main).unwrap().unwrap();
+            assert!(store.data_mut().is_empty(), "first boot is empty");
+            store.data_mut().extend_from_slice(b"efi vars");
+            // No explicit flush() here: Drop must persist this.
+        }
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"efi vars".to_vec());
+
+        let store = NvramStore::load(&main).unwrap().unwrap();
+        assert_eq!(store.data.as_slice(), b"efi vars");
+    }
+}
@@ -5,6 +5,7 @@
 use std::fmt::{self, Debug};
 use std::sync::Condvar as StdCondvar;
 use std::sync::Mutex as StdMutex;
+use std::sync::RwLock as StdRwLock;
 use std::sync::TryLockError;
 
 /// Infallible wrapper for [std::sync::Mutex]
@@ -134,3 +135,108 @@ impl Condvar {
         self.0.notify_all()
     }
 }
+
+/// Infallible wrapper for [std::sync::RwLock]
+#[repr(transparent)]
+pub struct RwLock<T: ?Sized>(StdRwLock<T>);
+
+// Re-export `RwLockReadGuard`/`RwLockWriteGuard` directly
+pub use std::sync::RwLockReadGuard;
+pub use std::sync::RwLockWriteGuard;
+
+impl<T> RwLock<T> {
+    /// Create a new rwlock in an unlocked state, ready for use.
+    pub fn new(data: T) -> Self {
+        Self(StdRwLock::new(data))
+    }
+
+    pub fn into_inner(self) -> T {
+        if let Ok(this) = self.0.into_inner() {
+            this
+        } else {
+            panic!("poisoned rwlock");
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Infallible equivalent to [std::sync::RwLock::read()]
+    ///
+    /// Will panic if the underlying RwLock becomes poisoned, but frees the
+    /// caller from having to check or unwrap() a [std::sync::LockResult].
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        if let Ok(guard) = self.0.read() {
+            guard
+        } else {
+            panic!("poisoned rwlock");
+        }
+    }
+
+    /// Infallible equivalent to [std::sync::RwLock::write()]
+    ///
+    /// Will panic if the underlying RwLock becomes poisoned, but frees the
+    /// caller from having to check or unwrap() a [std::sync::LockResult].
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        if let Ok(guard) = self.0.write() {
+            guard
+        } else {
+            panic!("poisoned rwlock");
+        }
+    }
+
+    /// Infallible equivalent to [std::sync::RwLock::try_read()]
+    ///
+    /// Returns `Some` if the rwlock was able to be acquired, `None` if the
+    /// rwlock could not be acquired (held exclusively by another thread),
+    /// and panics if the rwlock is poisoned.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        match self.0.try_read() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::WouldBlock) => None,
+            Err(TryLockError::Poisoned(_)) => {
+                panic!("poisoned rwlock");
+            }
+        }
+    }
+
+    /// Infallible equivalent to [std::sync::RwLock::try_write()]
+    ///
+    /// Returns `Some` if the rwlock was able to be acquired, `None` if the
+    /// rwlock could not be acquired (held by another thread), and panics if
+    /// the rwlock is poisoned.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        match self.0.try_write() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::WouldBlock) => None,
+            Err(TryLockError::Poisoned(_)) => {
+                panic!("poisoned rwlock");
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        if let Ok(this) = self.0.get_mut() {
+            this
+        } else {
+            panic!("poisoned rwlock");
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(value: T) -> Self {
+        RwLock::new(value)
+    }
+}